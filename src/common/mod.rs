@@ -12,11 +12,16 @@ pub use self::error::CommonError;
 pub use self::message::{BootstrapDenyReason, Message};
 pub use self::state::State;
 use safe_crypto::PublicEncryptKey;
+use socket_collection::Priority;
 use std::collections::HashSet;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 pub const HASH_SIZE: usize = 32;
 pub type NameHash = [u8; HASH_SIZE];
+/// Priority of the messages exchanged while establishing a connection. This is the highest
+/// priority (lowest value), so they're sent ahead of user data queued with any other priority.
+/// User data sent with priority 0 is treated the same as handshake messages.
+pub const HANDSHAKE_PRIORITY: Priority = 0;
 pub type Result<T> = ::std::result::Result<T, CommonError>;
 
 /// Specify crust user. Behaviour (for example in bootstrap phase) will be different for different
@@ -74,7 +79,72 @@ pub fn ipv4_addr(a: u8, b: u8, c: u8, d: u8, port: u16) -> SocketAddr {
     SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), port))
 }
 
+/// Compares name hashes in constant time, so that the time it takes doesn't reveal how many
+/// leading bytes of the hashes match.
+pub fn name_hash_eq(a: &NameHash, b: &NameHash) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks if `name_hash` is either `our_nh` or a hash of one of the `accepted_names`.
+pub fn is_accepted_name_hash(
+    name_hash: &NameHash,
+    our_nh: &NameHash,
+    accepted_names: &Option<Vec<String>>,
+) -> bool {
+    name_hash_eq(name_hash, our_nh)
+        || accepted_names
+            .iter()
+            .flatten()
+            .any(|name| name_hash_eq(name_hash, &safe_crypto::hash(name.as_bytes())))
+}
+
 mod core;
 mod error;
 mod message;
 mod state;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_hash_eq_returns_true_for_equal_hashes() {
+        assert!(name_hash_eq(&[0; HASH_SIZE], &[0; HASH_SIZE]));
+        assert!(name_hash_eq(&[7; HASH_SIZE], &[7; HASH_SIZE]));
+    }
+
+    #[test]
+    fn name_hash_eq_returns_false_for_different_hashes() {
+        let hash = [7; HASH_SIZE];
+        let mut first_byte_differs = hash;
+        first_byte_differs[0] = 8;
+        let mut last_byte_differs = hash;
+        last_byte_differs[HASH_SIZE - 1] = 8;
+
+        assert!(!name_hash_eq(&hash, &first_byte_differs));
+        assert!(!name_hash_eq(&hash, &last_byte_differs));
+    }
+
+    #[test]
+    fn is_accepted_name_hash_accepts_hashes_of_accepted_names() {
+        let our_nh = [7; HASH_SIZE];
+        let accepted_names = Some(vec!["old-network".to_owned()]);
+
+        assert!(is_accepted_name_hash(&our_nh, &our_nh, &accepted_names));
+        assert!(is_accepted_name_hash(
+            &safe_crypto::hash(b"old-network"),
+            &our_nh,
+            &accepted_names
+        ));
+        assert!(!is_accepted_name_hash(
+            &safe_crypto::hash(b"other-network"),
+            &our_nh,
+            &accepted_names
+        ));
+        assert!(!is_accepted_name_hash(
+            &safe_crypto::hash(b"old-network"),
+            &our_nh,
+            &None
+        ));
+    }
+}
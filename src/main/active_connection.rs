@@ -8,17 +8,19 @@
 // Software.
 
 use crate::common::{CoreTimer, CrustUser, Message, State};
-use crate::main::{ConnectionId, CrustData, Event, EventLoopCore};
+use crate::main::{ConnectionId, CrustData, CrustError, Event, EventLoopCore};
 use crate::PeerId;
 use mio::{Poll, Ready, Token};
 use mio_extras::timer::Timeout;
+use rand::{self, Rng};
 use socket_collection::{Priority, TcpSock};
 use std::any::Any;
 use std::cell::RefCell;
+use std::cmp;
 use std::collections::hash_map::Entry;
 use std::net::SocketAddr;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[cfg(not(test))]
 pub const INACTIVITY_TIMEOUT_MS: u64 = 120_000;
@@ -38,6 +40,8 @@ pub struct ActiveConnection {
     their_role: CrustUser,
     event_tx: crate::CrustEventSender,
     heartbeat: Heartbeat,
+    last_activity: Instant,
+    handshake_duration: Duration,
 }
 
 impl ActiveConnection {
@@ -51,6 +55,7 @@ impl ActiveConnection {
         their_role: CrustUser,
         event: Event,
         event_tx: crate::CrustEventSender,
+        handshake_started: Instant,
     ) {
         trace!(
             "Entered state ActiveConnection: {:?} -> {:?}",
@@ -67,6 +72,8 @@ impl ActiveConnection {
             their_role,
             event_tx,
             heartbeat,
+            last_activity: Instant::now(),
+            handshake_duration: handshake_started.elapsed(),
         }));
         let _ = core.insert_state(token, state.clone());
 
@@ -92,6 +99,7 @@ impl ActiveConnection {
         loop {
             match self.socket.read::<Message>() {
                 Ok(Some(Message::Data(data))) => {
+                    self.last_activity = Instant::now();
                     let _ =
                         self.event_tx
                             .send(Event::NewMessage(self.their_id, self.their_role, data));
@@ -116,7 +124,6 @@ impl ActiveConnection {
     #[cfg(not(test))]
     /// Helper function that returns a socket address of the connection
     pub fn peer_addr(&self) -> crate::Res<SocketAddr> {
-        use crate::main::CrustError;
         self.socket.peer_addr().map_err(CrustError::SocketError)
     }
 
@@ -127,10 +134,28 @@ impl ActiveConnection {
         Ok(unwrap!(FromStr::from_str("192.168.0.1:0")))
     }
 
+    /// Returns how long it took to establish the connection, from starting the handshake until
+    /// this state was entered.
+    pub fn handshake_duration(&self) -> Duration {
+        self.handshake_duration
+    }
+
+    /// Returns our socket address of the connection.
+    pub fn local_addr(&self) -> crate::Res<SocketAddr> {
+        self.socket.local_addr().map_err(CrustError::SocketError)
+    }
+
     pub fn peer_kind(&self) -> CrustUser {
         self.their_role
     }
 
+    /// Returns when we last queued data for sending or received data on this connection. Queued
+    /// data counts even if it hasn't been flushed yet. Heartbeats don't count in either direction,
+    /// so an idle connection keeps its last activity while it's kept alive.
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
     fn write(&mut self, core: &mut EventLoopCore, poll: &Poll, msg: Option<(Message, Priority)>) {
         if let Err(e) = self.socket.write(msg) {
             debug!("{:?} - Failed to write socket: {:?}", self.our_id, e);
@@ -164,6 +189,7 @@ impl State<CrustData> for ActiveConnection {
     }
 
     fn write(&mut self, core: &mut EventLoopCore, poll: &Poll, data: Vec<u8>, priority: Priority) {
+        self.last_activity = Instant::now();
         self.write(core, poll, Some((Message::Data(data), priority)));
         self.reset_send_heartbeat(core, poll);
     }
@@ -212,22 +238,29 @@ struct Heartbeat {
     recv_timer: CoreTimer,
     send_timeout: Timeout,
     send_timer: CoreTimer,
+    jitter_ms: u64,
 }
 
 impl Heartbeat {
     fn new(core: &mut EventLoopCore, state_id: Token) -> Self {
+        let jitter_ms = core.user_data().config.cfg.heartbeat_jitter_ms.unwrap_or(0);
+
         let recv_timer = CoreTimer::new(state_id, 0);
         let recv_timeout =
             core.set_timeout(Duration::from_millis(INACTIVITY_TIMEOUT_MS), recv_timer);
 
         let send_timer = CoreTimer::new(state_id, 1);
-        let send_timeout = core.set_timeout(Duration::from_millis(HEARTBEAT_PERIOD_MS), send_timer);
+        let send_timeout = core.set_timeout(
+            heartbeat_period(&mut rand::thread_rng(), jitter_ms),
+            send_timer,
+        );
 
         Self {
             recv_timeout,
             recv_timer,
             send_timeout,
             send_timer,
+            jitter_ms,
         }
     }
 
@@ -235,8 +268,7 @@ impl Heartbeat {
         if timer_id == self.recv_timer.timer_id {
             HeartbeatAction::Terminate
         } else {
-            self.send_timeout =
-                core.set_timeout(Duration::from_millis(HEARTBEAT_PERIOD_MS), self.send_timer);
+            self.send_timeout = core.set_timeout(self.send_period(), self.send_timer);
             HeartbeatAction::Send
         }
     }
@@ -252,11 +284,14 @@ impl Heartbeat {
 
     fn reset_send(&mut self, core: &mut EventLoopCore) -> crate::Res<()> {
         let _ = core.cancel_timeout(&self.send_timeout);
-        self.send_timeout =
-            core.set_timeout(Duration::from_millis(HEARTBEAT_PERIOD_MS), self.send_timer);
+        self.send_timeout = core.set_timeout(self.send_period(), self.send_timer);
         Ok(())
     }
 
+    fn send_period(&self) -> Duration {
+        heartbeat_period(&mut rand::thread_rng(), self.jitter_ms)
+    }
+
     fn terminate(&mut self, core: &mut EventLoopCore) {
         let _ = core.cancel_timeout(&self.recv_timeout);
         let _ = core.cancel_timeout(&self.send_timeout);
@@ -267,3 +302,49 @@ enum HeartbeatAction {
     Send,
     Terminate,
 }
+
+/// Returns the heartbeat period shortened by a random amount of up to `jitter_ms` milliseconds.
+/// The period is only ever shortened, so jitter can't make the remote peer consider us inactive.
+fn heartbeat_period<R: Rng>(rng: &mut R, jitter_ms: u64) -> Duration {
+    let jitter_ms = cmp::min(jitter_ms, HEARTBEAT_PERIOD_MS / 2);
+    Duration::from_millis(HEARTBEAT_PERIOD_MS - rng.gen_range(0, jitter_ms + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_period_without_jitter_is_constant() {
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            assert_eq!(
+                heartbeat_period(&mut rng, 0),
+                Duration::from_millis(HEARTBEAT_PERIOD_MS)
+            );
+        }
+    }
+
+    #[test]
+    fn heartbeat_period_falls_within_jitter_range() {
+        let mut rng = rand::thread_rng();
+        let jitter_ms = HEARTBEAT_PERIOD_MS / 4;
+
+        for _ in 0..100 {
+            let period = heartbeat_period(&mut rng, jitter_ms);
+            assert!(period <= Duration::from_millis(HEARTBEAT_PERIOD_MS));
+            assert!(period >= Duration::from_millis(HEARTBEAT_PERIOD_MS - jitter_ms));
+        }
+    }
+
+    #[test]
+    fn heartbeat_jitter_is_capped_at_half_the_period() {
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            let period = heartbeat_period(&mut rng, 10 * HEARTBEAT_PERIOD_MS);
+            assert!(period >= Duration::from_millis(HEARTBEAT_PERIOD_MS / 2));
+        }
+    }
+}
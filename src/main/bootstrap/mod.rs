@@ -35,7 +35,7 @@ use std::mem;
 use std::net::SocketAddr;
 use std::rc::{Rc, Weak};
 use std::sync::mpsc::{self, Receiver};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const BOOTSTRAP_TIMEOUT_SEC: u64 = 10;
 const SERVICE_DISCOVERY_TIMEOUT_SEC: u64 = 1;
@@ -128,11 +128,14 @@ impl Bootstrap {
             return self.terminate(core, poll);
         }
 
+        let started_at = Instant::now();
         for peer in peers {
             let self_weak = self.self_weak.clone();
             let finish = move |core: &mut EventLoopCore, poll: &Poll, child, res| {
                 if let Some(self_rc) = self_weak.upgrade() {
-                    self_rc.borrow_mut().handle_result(core, poll, child, res)
+                    self_rc
+                        .borrow_mut()
+                        .handle_result(core, poll, child, res, started_at)
                 }
             };
 
@@ -159,6 +162,7 @@ impl Bootstrap {
         poll: &Poll,
         child: Token,
         res: Result<(TcpSock, PeerInfo, PeerId), (PeerInfo, Option<BootstrapDenyReason>)>,
+        started_at: Instant,
     ) {
         let _ = self.children.remove(&child);
         match res {
@@ -175,6 +179,7 @@ impl Bootstrap {
                     CrustUser::Node,
                     Event::BootstrapConnect(peer_id, peer_info.addr),
                     self.event_tx.clone(),
+                    started_at,
                 );
             }
             Err((bad_peer, opt_reason)) => {
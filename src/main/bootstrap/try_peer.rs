@@ -7,7 +7,9 @@
 // specific language governing permissions and limitations relating to use of the SAFE Network
 // Software.
 
-use crate::common::{BootstrapDenyReason, BootstrapperRole, Message, NameHash, PeerInfo, State};
+use crate::common::{
+    BootstrapDenyReason, BootstrapperRole, Message, NameHash, PeerInfo, State, HANDSHAKE_PRIORITY,
+};
 use crate::main::{CrustData, EventLoopCore};
 use crate::PeerId;
 use mio::{Poll, PollOpt, Ready, Token};
@@ -65,7 +67,10 @@ impl TryPeer {
             token,
             peer,
             socket,
-            request: Some((Message::BootstrapRequest(our_uid, name_hash, our_role), 0)),
+            request: Some((
+                Message::BootstrapRequest(our_uid, name_hash, our_role),
+                HANDSHAKE_PRIORITY,
+            )),
             finish,
             shared_key,
         };
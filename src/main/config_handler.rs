@@ -12,7 +12,7 @@ use crate::main::BootstrapCacheConfig;
 use config_file_handler::{self, FileHandler};
 use std::collections::HashSet;
 use std::ffi::OsString;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
 #[cfg(test)]
 use std::path::PathBuf;
@@ -52,6 +52,47 @@ pub struct Config {
     /// This is a mechanism to prevent nodes from different decentralized
     /// networks to connect to each other (issue #209)
     pub network_name: Option<String>,
+    /// Other network names we accept peers from, e.g. while migrating to a new `network_name`.
+    /// We still present only the hash of `network_name` to our peers.
+    pub accepted_network_names: Option<Vec<String>>,
+    /// Local address to bind outgoing direct connections to, e.g. `0.0.0.0:5483` to only fix the
+    /// source port. Using a known source port makes the mapping NATs give to our connections
+    /// predictable. The port may be shared with other sockets. If binding fails, crust logs a
+    /// warning and lets the OS choose the local address instead. It's ignored for peer addresses
+    /// of the other IP version.
+    pub outgoing_bind_addr: Option<SocketAddr>,
+    /// IP time-to-live of outgoing direct connections. The OS default is used if not set.
+    pub outgoing_ttl: Option<u32>,
+    /// If `true`, connecting to a peer fails right away when none of our network interfaces has a
+    /// routable address that could reach any of the peer's addresses. Loopback, link-local and
+    /// unspecified interface addresses don't count. Off by default.
+    pub fail_connect_without_network: Option<bool>,
+    /// Maximum number of direct addresses dialed when connecting to a peer. Any further addresses
+    /// in the peer's connection info are ignored. Defaults to 32.
+    pub max_direct_addrs: Option<usize>,
+    /// Maximum number of direct connection attempts to a peer that are in progress at the same
+    /// time. The remaining addresses are dialed as earlier attempts finish. Defaults to 8. A value
+    /// of 0 is treated as 1.
+    pub max_concurrent_connects: Option<usize>,
+    /// Maximum random amount of milliseconds each heartbeat period is shortened by. This keeps
+    /// heartbeats to many peers from being sent in lockstep. Capped at half of the heartbeat
+    /// period. Defaults to no jitter.
+    pub heartbeat_jitter_ms: Option<u64>,
+    /// Number of seconds to wait for a connection to a peer to be established before giving up.
+    /// Defaults to 60 seconds.
+    pub connect_timeout_sec: Option<u64>,
+    /// Number of seconds a single direct connection attempt may take to complete its handshake.
+    /// A stalled attempt is dropped, freeing its slot for other addresses. Defaults to
+    /// `connect_timeout_sec`.
+    pub connect_attempt_timeout_sec: Option<u64>,
+    /// Number of times a failed direct connection attempt to an address is retried. Defaults to
+    /// no retries.
+    pub connect_retries: Option<u32>,
+    /// Milliseconds to wait before the first retry of a failed direct connection attempt.
+    /// Defaults to 200 ms.
+    pub connect_retry_delay_ms: Option<u64>,
+    /// Factor the retry delay is multiplied by after each retry. Defaults to 2.
+    pub connect_retry_backoff: Option<u64>,
 }
 
 impl Default for Config {
@@ -66,6 +107,18 @@ impl Default for Config {
             whitelisted_node_ips: None,
             whitelisted_client_ips: None,
             network_name: None,
+            accepted_network_names: None,
+            outgoing_bind_addr: None,
+            outgoing_ttl: None,
+            fail_connect_without_network: None,
+            max_direct_addrs: None,
+            max_concurrent_connects: None,
+            heartbeat_jitter_ms: None,
+            connect_timeout_sec: None,
+            connect_attempt_timeout_sec: None,
+            connect_retries: None,
+            connect_retry_delay_ms: None,
+            connect_retry_backoff: None,
         }
     }
 }
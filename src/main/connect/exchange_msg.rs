@@ -7,10 +7,13 @@
 // specific language governing permissions and limitations relating to use of the SAFE Network
 // Software.
 
-use crate::common::{Message, NameHash, State};
+use crate::common::{
+    is_accepted_name_hash, CoreTimer, Message, NameHash, State, HANDSHAKE_PRIORITY,
+};
 use crate::main::{ConnectionId, CrustData, EventLoopCore};
 use crate::PeerId;
 use mio::{Poll, PollOpt, Ready, Token};
+use mio_extras::timer::Timeout;
 use safe_crypto::SharedSecretKey;
 use socket_collection::{EncryptContext, Priority, TcpSock};
 use std::any::Any;
@@ -20,6 +23,7 @@ use std::collections::HashSet;
 use std::mem;
 use std::net::SocketAddr;
 use std::rc::Rc;
+use std::time::Duration;
 
 /// When connection messages are exchanged a callback is called with these parameters.
 /// A new mio `Token` is assigned to the given socket.
@@ -33,6 +37,7 @@ pub struct ExchangeMsg {
     socket: TcpSock,
     msg: Option<(Message, Priority)>,
     shared_key: SharedSecretKey,
+    timeout: Timeout,
     finish: Finish,
 }
 
@@ -46,6 +51,7 @@ impl ExchangeMsg {
         name_hash: NameHash,
         shared_key: SharedSecretKey,
         our_global_direct_listeners: HashSet<SocketAddr>,
+        timeout: Duration,
         finish: Finish,
     ) -> crate::Res<Token> {
         let token = core.get_new_token();
@@ -57,6 +63,8 @@ impl ExchangeMsg {
             PollOpt::edge(),
         )?;
 
+        let timeout = core.set_timeout(timeout, CoreTimer::new(token, 0));
+
         let connections = &mut core.user_data_mut().connections;
         connections
             .entry(expected_id)
@@ -78,9 +86,10 @@ impl ExchangeMsg {
             socket,
             msg: Some((
                 Message::ConnectRequest(our_id, name_hash, our_global_direct_listeners),
-                0,
+                HANDSHAKE_PRIORITY,
             )),
             shared_key,
+            timeout,
             finish,
         };
 
@@ -98,10 +107,14 @@ impl ExchangeMsg {
     fn receive_response(&mut self, core: &mut EventLoopCore, poll: &Poll) {
         match self.socket.read::<Message>() {
             Ok(Some(Message::ConnectResponse(their_uid, name_hash))) => {
-                if their_uid != self.expected_id || name_hash != self.expected_nh {
+                let accepted_names = &core.user_data().config.cfg.accepted_network_names;
+                if their_uid != self.expected_id
+                    || !is_accepted_name_hash(&name_hash, &self.expected_nh, accepted_names)
+                {
                     return self.handle_error(core, poll);
                 }
                 let _ = core.remove_state(self.token);
+                let _ = core.cancel_timeout(&self.timeout);
                 let token = self.token;
 
                 let mut socket = mem::replace(&mut self.socket, Default::default());
@@ -139,6 +152,7 @@ impl State<CrustData> for ExchangeMsg {
 
     fn terminate(&mut self, core: &mut EventLoopCore, poll: &Poll) {
         let _ = core.remove_state(self.token);
+        let _ = core.cancel_timeout(&self.timeout);
         let _ = poll.deregister(&self.socket);
 
         let connections = &mut core.user_data_mut().connections;
@@ -155,6 +169,14 @@ impl State<CrustData> for ExchangeMsg {
         );
     }
 
+    fn timeout(&mut self, core: &mut EventLoopCore, poll: &Poll, _timer_id: u8) {
+        debug!(
+            "Connection attempt to {:?} timed out during handshake",
+            self.expected_id
+        );
+        self.handle_error(core, poll);
+    }
+
     fn as_any(&mut self) -> &mut Any {
         self
     }
@@ -13,22 +13,33 @@ use self::exchange_msg::ExchangeMsg;
 use crate::common::{CoreTimer, CrustUser, NameHash, PeerInfo, State};
 use crate::main::bootstrap;
 use crate::main::{
-    ActiveConnection, ConnectionCandidate, CrustData, CrustError, Event, EventLoopCore,
-    PrivConnectionInfo, PubConnectionInfo,
+    ActiveConnection, ConnectionCandidate, ConnectionId, CrustData, CrustError, Event,
+    EventLoopCore, PrivConnectionInfo, PubConnectionInfo,
 };
+use crate::nat::new_reusably_bound_tcp_socket;
 use crate::PeerId;
+use get_if_addrs::{self, Interface};
+use mio::net::TcpStream;
 use mio::{Poll, Token};
 use mio_extras::timer::Timeout;
+use net2::TcpBuilder;
 use safe_crypto::{SecretEncryptKey, SharedSecretKey};
 use socket_collection::{DecryptContext, EncryptContext, TcpSock};
 use std::any::Any;
 use std::cell::RefCell;
-use std::collections::HashSet;
-use std::net::SocketAddr;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::rc::{Rc, Weak};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const TIMEOUT_SEC: u64 = 60;
+const TIMEOUT_TIMER_ID: u8 = 0;
+const DEFAULT_MAX_DIRECT_ADDRS: usize = 32;
+const DEFAULT_MAX_CONCURRENT_CONNECTS: usize = 8;
+const DEFAULT_RETRY_DELAY_MS: u64 = 200;
+const DEFAULT_RETRY_BACKOFF: u64 = 2;
 
 /// Atempts multiple connections to remote peer, but yields the first successful one.
 pub struct Connect {
@@ -41,6 +52,40 @@ pub struct Connect {
     children: HashSet<Token>,
     event_tx: crate::CrustEventSender,
     our_global_direct_listeners: HashSet<SocketAddr>,
+    shared_key: SharedSecretKey,
+    retry: RetryPolicy,
+    /// Addresses waiting to be dialed again, keyed by the id of their retry timer.
+    pending_retries: HashMap<u8, PendingRetry>,
+    /// Addresses waiting for a free connection slot, with the number of retries done for each.
+    queued: VecDeque<(PeerInfo, u32)>,
+    max_concurrent: usize,
+    attempt_timeout: Duration,
+    /// Whether we count as handshaking in the peer's `ConnectionId`, which we do while addresses
+    /// wait to be dialed and so no `ExchangeMsg` might be alive for the peer.
+    is_handshaking: bool,
+}
+
+/// How failed direct connection attempts are retried.
+struct RetryPolicy {
+    max_retries: u32,
+    delay_ms: u64,
+    backoff: u64,
+}
+
+impl RetryPolicy {
+    /// Returns how long to wait before the given retry, counting from 0.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay_ms = (0..retry).fold(self.delay_ms, |delay_ms, _| {
+            delay_ms.saturating_mul(self.backoff)
+        });
+        Duration::from_millis(delay_ms)
+    }
+}
+
+struct PendingRetry {
+    timeout: Timeout,
+    peer_info: PeerInfo,
+    retries_done: u32,
 }
 
 impl Connect {
@@ -55,19 +100,76 @@ impl Connect {
         our_global_direct_listeners: HashSet<SocketAddr>,
     ) -> crate::Res<()> {
         let their_id = their_ci.id;
-        let their_direct = their_ci.for_direct;
+        let mut their_direct = their_ci.for_direct;
 
         if their_direct.is_empty() {
             let _ = event_tx.send(Event::ConnectFailure(their_id));
             return Err(CrustError::InsufficientConnectionInfo);
         }
 
+        let mut seen_addrs = HashSet::with_capacity(their_direct.len());
+        their_direct.retain(|addr| seen_addrs.insert(*addr));
+
+        let max_direct_addrs = core
+            .user_data()
+            .config
+            .cfg
+            .max_direct_addrs
+            .unwrap_or(DEFAULT_MAX_DIRECT_ADDRS);
+        if their_direct.len() > max_direct_addrs {
+            warn!(
+                "Peer {:?} gave {} direct addresses, dialing only the first {}",
+                their_id,
+                their_direct.len(),
+                max_direct_addrs
+            );
+            their_direct.truncate(max_direct_addrs);
+        }
+
+        if core
+            .user_data()
+            .config
+            .cfg
+            .fail_connect_without_network
+            .unwrap_or(false)
+        {
+            match get_if_addrs::get_if_addrs() {
+                Ok(ref interfaces) if !is_network_available(interfaces, &their_direct) => {
+                    let _ = event_tx.send(Event::ConnectFailure(their_id));
+                    return Err(CrustError::NoNetwork);
+                }
+                Ok(_) => (),
+                Err(e) => debug!("Failed to list network interfaces: {}", e),
+            }
+        }
+
         let token = core.get_new_token();
+        let config = &core.user_data().config.cfg;
+        let timeout_sec = config.connect_timeout_sec.unwrap_or(TIMEOUT_SEC);
+        let attempt_timeout_sec = config.connect_attempt_timeout_sec.unwrap_or(timeout_sec);
+        // With no attempts allowed at all, we'd never dial anything and only fail on timeout.
+        let max_concurrent = config
+            .max_concurrent_connects
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_CONNECTS)
+            .max(1);
+        let retry = RetryPolicy {
+            max_retries: config.connect_retries.unwrap_or(0),
+            delay_ms: config
+                .connect_retry_delay_ms
+                .unwrap_or(DEFAULT_RETRY_DELAY_MS),
+            backoff: config
+                .connect_retry_backoff
+                .unwrap_or(DEFAULT_RETRY_BACKOFF),
+        };
 
         let our_id = our_ci.id;
+        let their_pk = their_ci.id.pub_enc_key;
         let state = Rc::new(RefCell::new(Self {
             token,
-            timeout: core.set_timeout(Duration::from_secs(TIMEOUT_SEC), CoreTimer::new(token, 0)),
+            timeout: core.set_timeout(
+                Duration::from_secs(timeout_sec),
+                CoreTimer::new(token, TIMEOUT_TIMER_ID),
+            ),
             our_nh,
             our_id,
             their_id,
@@ -75,35 +177,154 @@ impl Connect {
             children: HashSet::with_capacity(their_direct.len()),
             event_tx,
             our_global_direct_listeners,
+            shared_key: our_sk.shared_secret(&their_pk),
+            retry,
+            pending_retries: HashMap::new(),
+            queued: their_direct
+                .into_iter()
+                .map(|addr| (PeerInfo::new(addr, their_pk), 0))
+                .collect(),
+            max_concurrent,
+            attempt_timeout: Duration::from_secs(attempt_timeout_sec),
+            is_handshaking: false,
         }));
 
         state.borrow_mut().self_weak = Rc::downgrade(&state);
+        state.borrow_mut().dial_queued(core, poll);
 
-        let their_pk = their_ci.id.pub_enc_key;
-        let sockets = their_direct
-            .into_iter()
-            .filter_map(|addr| {
-                let info = PeerInfo::new(addr, their_pk);
-                TcpSock::connect(&addr).map(|sock| (sock, info)).ok()
-            })
-            .collect::<Vec<_>>();
+        let _ = core.insert_state(token, state.clone());
+        // All dials might have failed right away.
+        state.borrow_mut().maybe_terminate(core, poll);
 
-        for (mut socket, peer_info) in sockets {
-            let shared_key = our_sk.shared_secret(&their_pk);
-            match (
-                socket.set_encrypt_ctx(EncryptContext::anonymous_encrypt(their_pk)),
-                socket.set_decrypt_ctx(DecryptContext::authenticated(shared_key.clone())),
-            ) {
-                (Ok(_), Ok(_)) => state
-                    .borrow_mut()
-                    .exchange_msg(core, poll, socket, peer_info, shared_key),
-                res => warn!("Failed to set encrypt/decrypt context: {:?}", res),
+        Ok(())
+    }
+
+    /// Dials queued addresses until `max_concurrent` connections are in progress.
+    fn dial_queued(&mut self, core: &mut EventLoopCore, poll: &Poll) {
+        while self.children.len() < self.max_concurrent {
+            match self.queued.pop_front() {
+                Some((peer_info, retries_done)) => self.dial(core, poll, peer_info, retries_done),
+                None => break,
             }
         }
+        self.update_handshaking(core);
+    }
 
-        let _ = core.insert_state(token, state);
+    /// Keeps the peer's `ConnectionId` alive while we have addresses waiting to be dialed, so
+    /// that we're still seen as connecting to the peer in between attempts.
+    fn update_handshaking(&mut self, core: &mut EventLoopCore) {
+        let is_handshaking = !self.pending_retries.is_empty() || !self.queued.is_empty();
+        if is_handshaking == self.is_handshaking {
+            return;
+        }
+        self.is_handshaking = is_handshaking;
 
-        Ok(())
+        let connections = &mut core.user_data_mut().connections;
+        if is_handshaking {
+            connections
+                .entry(self.their_id)
+                .or_insert(ConnectionId {
+                    active_connection: None,
+                    currently_handshaking: 0,
+                })
+                .currently_handshaking += 1;
+        } else if let Entry::Occupied(mut oe) = connections.entry(self.their_id) {
+            oe.get_mut().currently_handshaking -= 1;
+            if oe.get().currently_handshaking == 0 && oe.get().active_connection.is_none() {
+                let _ = oe.remove();
+            }
+        }
+        trace!(
+            "Connection Map updated: {:?} -> {:?}",
+            self.their_id,
+            connections.get(&self.their_id)
+        );
+    }
+
+    fn dial(
+        &mut self,
+        core: &mut EventLoopCore,
+        poll: &Poll,
+        peer_info: PeerInfo,
+        retries_done: u32,
+    ) {
+        let dialed_at = Instant::now();
+        let bind_addr = core.user_data().config.cfg.outgoing_bind_addr;
+        let ttl = core.user_data().config.cfg.outgoing_ttl;
+        let mut socket = match connect_tcp(&peer_info.addr, bind_addr, ttl) {
+            Ok(stream) => TcpSock::wrap(stream),
+            Err(e) => {
+                debug!("Failed to connect to {}: {:?}", peer_info.addr, e);
+                // Errors like `ENETUNREACH` while we're offline say nothing about the peer, so it's
+                // kept in the bootstrap cache.
+                let _ = self.schedule_retry(core, peer_info, retries_done);
+                return;
+            }
+        };
+
+        let shared_key = self.shared_key.clone();
+        match (
+            socket.set_encrypt_ctx(EncryptContext::anonymous_encrypt(peer_info.pub_key)),
+            socket.set_decrypt_ctx(DecryptContext::authenticated(shared_key.clone())),
+        ) {
+            (Ok(_), Ok(_)) => self.exchange_msg(
+                core,
+                poll,
+                socket,
+                peer_info,
+                shared_key,
+                retries_done,
+                dialed_at,
+            ),
+            res => {
+                warn!("Failed to set encrypt/decrypt context: {:?}", res);
+                self.handle_failed_attempt(core, peer_info, retries_done);
+            }
+        }
+    }
+
+    /// Schedules a retry of a failed attempt, or removes the peer from the bootstrap cache once the
+    /// address is out of retries.
+    fn handle_failed_attempt(
+        &mut self,
+        core: &mut EventLoopCore,
+        peer_info: PeerInfo,
+        retries_done: u32,
+    ) {
+        if !self.schedule_retry(core, peer_info, retries_done) {
+            self.remove_peer_from_cache(core, &peer_info);
+        }
+    }
+
+    /// Schedules the given address to be dialed again, unless we've retried it enough times
+    /// already. Returns whether a retry was scheduled.
+    fn schedule_retry(
+        &mut self,
+        core: &mut EventLoopCore,
+        peer_info: PeerInfo,
+        retries_done: u32,
+    ) -> bool {
+        if retries_done >= self.retry.max_retries {
+            return false;
+        }
+        let timer_id = match (1..=u8::max_value()).find(|id| !self.pending_retries.contains_key(id))
+        {
+            Some(timer_id) => timer_id,
+            None => return false,
+        };
+
+        let delay = self.retry.delay(retries_done);
+        debug!("Retrying connection to {} in {:?}", peer_info.addr, delay);
+        let timeout = core.set_timeout(delay, CoreTimer::new(self.token, timer_id));
+        let _ = self.pending_retries.insert(
+            timer_id,
+            PendingRetry {
+                timeout,
+                peer_info,
+                retries_done: retries_done + 1,
+            },
+        );
+        true
     }
 
     fn exchange_msg(
@@ -113,17 +334,25 @@ impl Connect {
         socket: TcpSock,
         peer_info: PeerInfo,
         shared_key: SharedSecretKey,
+        retries_done: u32,
+        dialed_at: Instant,
     ) {
         let self_weak = self.self_weak.clone();
         let handler = move |core: &mut EventLoopCore, poll: &Poll, child, res| {
             if let Some(self_rc) = self_weak.upgrade() {
-                self_rc
-                    .borrow_mut()
-                    .handle_exchange_msg(core, poll, child, res, peer_info);
+                self_rc.borrow_mut().handle_exchange_msg(
+                    core,
+                    poll,
+                    child,
+                    res,
+                    peer_info,
+                    retries_done,
+                    dialed_at,
+                );
             }
         };
 
-        if let Ok(child) = ExchangeMsg::start(
+        match ExchangeMsg::start(
             core,
             poll,
             socket,
@@ -132,11 +361,17 @@ impl Connect {
             self.our_nh,
             shared_key,
             self.our_global_direct_listeners.clone(),
+            self.attempt_timeout,
             Box::new(handler),
         ) {
-            let _ = self.children.insert(child);
+            Ok(child) => {
+                let _ = self.children.insert(child);
+            }
+            Err(e) => {
+                debug!("Failed to exchange connect messages: {:?}", e);
+                self.handle_failed_attempt(core, peer_info, retries_done);
+            }
         }
-        self.maybe_terminate(core, poll);
     }
 
     fn handle_exchange_msg(
@@ -146,6 +381,8 @@ impl Connect {
         child: Token,
         res: Option<TcpSock>,
         peer_info: PeerInfo,
+        retries_done: u32,
+        dialed_at: Instant,
     ) {
         let _ = self.children.remove(&child);
         if let Some(socket) = res {
@@ -155,7 +392,7 @@ impl Connect {
                 if let Some(self_rc) = self_weak.upgrade() {
                     self_rc
                         .borrow_mut()
-                        .handle_connection_candidate(core, poll, child, res);
+                        .handle_connection_candidate(core, poll, child, res, dialed_at);
                 }
             };
 
@@ -171,8 +408,9 @@ impl Connect {
                 let _ = self.children.insert(child);
             }
         } else {
-            self.remove_peer_from_cache(core, &peer_info);
+            self.handle_failed_attempt(core, peer_info, retries_done);
         }
+        self.dial_queued(core, poll);
         self.maybe_terminate(core, poll);
     }
 
@@ -182,6 +420,7 @@ impl Connect {
         poll: &Poll,
         child: Token,
         res: Option<TcpSock>,
+        dialed_at: Instant,
     ) {
         let _ = self.children.remove(&child);
         if let Some(socket) = res {
@@ -197,8 +436,10 @@ impl Connect {
                 CrustUser::Node,
                 Event::ConnectSuccess(self.their_id),
                 self.event_tx.clone(),
+                dialed_at,
             );
         }
+        self.dial_queued(core, poll);
         self.maybe_terminate(core, poll);
     }
 
@@ -209,7 +450,7 @@ impl Connect {
     }
 
     fn maybe_terminate(&mut self, core: &mut EventLoopCore, poll: &Poll) {
-        if self.children.is_empty() {
+        if self.children.is_empty() && self.pending_retries.is_empty() && self.queued.is_empty() {
             self.terminate(core, poll);
         }
     }
@@ -227,14 +468,28 @@ impl Connect {
 }
 
 impl State<CrustData> for Connect {
-    fn timeout(&mut self, core: &mut EventLoopCore, poll: &Poll, _timer_id: u8) {
-        debug!("Connect to peer {:?} timed out", self.their_id);
-        self.terminate(core, poll);
+    fn timeout(&mut self, core: &mut EventLoopCore, poll: &Poll, timer_id: u8) {
+        if timer_id == TIMEOUT_TIMER_ID {
+            debug!("Connect to peer {:?} timed out", self.their_id);
+            return self.terminate(core, poll);
+        }
+
+        if let Some(retry) = self.pending_retries.remove(&timer_id) {
+            self.queued
+                .push_front((retry.peer_info, retry.retries_done));
+            self.dial_queued(core, poll);
+            self.maybe_terminate(core, poll);
+        }
     }
 
     fn terminate(&mut self, core: &mut EventLoopCore, poll: &Poll) {
         self.terminate_children(core, poll);
 
+        for (_, retry) in self.pending_retries.drain() {
+            let _ = core.cancel_timeout(&retry.timeout);
+        }
+        self.queued.clear();
+        self.update_handshaking(core);
         let _ = core.cancel_timeout(&self.timeout);
         let _ = core.remove_state(self.token);
 
@@ -248,6 +503,76 @@ impl State<CrustData> for Connect {
     }
 }
 
+/// Checks if any of the given addresses could be reached from our interfaces. Loopback addresses
+/// are always reachable, others need an interface of the same address family with a routable
+/// address.
+fn is_network_available(interfaces: &[Interface], addrs: &[SocketAddr]) -> bool {
+    addrs.iter().any(|addr| {
+        addr.ip().is_loopback()
+            || interfaces.iter().any(|interface| {
+                let ip = interface.ip();
+                is_routable_interface_ip(&ip) && ip.is_ipv4() == addr.is_ipv4()
+            })
+    })
+}
+
+/// Link-local addresses don't count, since they're assigned as soon as a link is up, even if
+/// there's no network behind it.
+fn is_routable_interface_ip(ip: &IpAddr) -> bool {
+    match *ip {
+        IpAddr::V4(ip) => !(ip.is_loopback() || ip.is_unspecified() || ip.is_link_local()),
+        IpAddr::V6(ip) => {
+            let is_link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+            !(ip.is_loopback() || ip.is_unspecified() || is_link_local)
+        }
+    }
+}
+
+/// Starts a TCP connection to the given address. If `bind_addr` is given and of the same address
+/// family, the socket is bound to it before connecting. When that fails (e.g. the port is in use),
+/// we fall back to an OS chosen local address. If `ttl` is given, it's set before connecting, so
+/// it applies to the SYN as well.
+fn connect_tcp(
+    addr: &SocketAddr,
+    bind_addr: Option<SocketAddr>,
+    ttl: Option<u32>,
+) -> io::Result<TcpStream> {
+    match bind_addr {
+        Some(bind_addr) if bind_addr.is_ipv4() == addr.is_ipv4() => {
+            match new_reusably_bound_tcp_socket(&bind_addr)
+                .and_then(|socket| connect_tcp_socket(socket, addr, ttl))
+            {
+                Ok(stream) => return Ok(stream),
+                Err(e) => warn!(
+                    "Could not connect to {} from {}: {:?}. Using an OS chosen local address.",
+                    addr, bind_addr, e
+                ),
+            }
+        }
+        Some(bind_addr) => debug!(
+            "Not binding connection to {} to {}: address families differ",
+            addr, bind_addr
+        ),
+        None => (),
+    }
+    let socket = match *addr {
+        SocketAddr::V4(..) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(..) => TcpBuilder::new_v6()?,
+    };
+    connect_tcp_socket(socket, addr, ttl)
+}
+
+fn connect_tcp_socket(
+    socket: TcpBuilder,
+    addr: &SocketAddr,
+    ttl: Option<u32>,
+) -> io::Result<TcpStream> {
+    if let Some(ttl) = ttl {
+        let _ = socket.ttl(ttl)?;
+    }
+    TcpStream::connect_stream(socket.to_tcp_stream()?, addr)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -259,6 +584,10 @@ mod tests {
             get_event_sender, peer_info_with_rand_key, rand_peer_id_and_enc_sk,
             test_bootstrap_cache, test_core,
         };
+        use std::net::TcpListener;
+        use std::sync::mpsc::Receiver;
+
+        const CONNECT_STATE_TOKEN: Token = Token(0);
 
         fn test_priv_conn_info() -> (PrivConnectionInfo, SecretEncryptKey) {
             let (id, sk) = rand_peer_id_and_enc_sk();
@@ -269,6 +598,53 @@ mod tests {
             (conn_info, sk)
         }
 
+        /// Starts connecting to a random peer at the given addresses and returns the peer's id.
+        fn start_connect(
+            core: &mut EventLoopCore,
+            poll: &Poll,
+            their_addrs: Vec<SocketAddr>,
+        ) -> (PeerId, Receiver<Event>) {
+            let (our_ci, our_sk) = test_priv_conn_info();
+            let (their_id, _) = rand_peer_id_and_enc_sk();
+            let their_ci = PubConnectionInfo {
+                id: their_id,
+                for_direct: their_addrs,
+            };
+
+            let (event_tx, event_rx) = get_event_sender();
+            unwrap!(Connect::start(
+                core,
+                poll,
+                our_ci,
+                their_ci,
+                [1; 32],
+                event_tx,
+                &our_sk,
+                Default::default(),
+            ));
+            (their_id, event_rx)
+        }
+
+        fn with_connect_state<F, R>(core: &mut EventLoopCore, f: F) -> R
+        where
+            F: FnOnce(&mut Connect, &mut EventLoopCore) -> R,
+        {
+            let state = unwrap!(core.get_state(CONNECT_STATE_TOKEN));
+            let mut state = state.borrow_mut();
+            f(unwrap!(state.as_any().downcast_mut::<Connect>()), core)
+        }
+
+        fn local_listeners(count: usize) -> (Vec<TcpListener>, Vec<SocketAddr>) {
+            let listeners = (0..count)
+                .map(|_| unwrap!(TcpListener::bind("127.0.0.1:0")))
+                .collect::<Vec<_>>();
+            let addrs = listeners
+                .iter()
+                .map(|listener| unwrap!(listener.local_addr()))
+                .collect();
+            (listeners, addrs)
+        }
+
         #[test]
         fn remove_peer_from_cache_does_what_it_says() {
             let cached_peer = peer_info_with_rand_key(ipv4_addr(1, 2, 3, 4, 4000));
@@ -276,32 +652,420 @@ mod tests {
             let _ = bootstrap_cache.put(cached_peer);
             let mut core = test_core(bootstrap_cache);
             let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(1);
+            let _ = start_connect(&mut core, &poll, addrs);
+
+            with_connect_state(&mut core, |connect_state, core| {
+                connect_state.remove_peer_from_cache(core, &cached_peer);
+            });
+
+            let cached_peers = core.user_data().bootstrap_cache.snapshot();
+            assert!(cached_peers.is_empty());
+        }
+
+        #[test]
+        fn it_fails_immediately_without_direct_addrs() {
+            let mut core = test_core(test_bootstrap_cache());
+            let poll = unwrap!(Poll::new());
 
             let (our_ci, our_sk) = test_priv_conn_info();
-            let (their_ci, _) = test_priv_conn_info();
-            let their_ci = their_ci.to_pub_connection_info();
+            let (their_id, _) = rand_peer_id_and_enc_sk();
+            let their_ci = PubConnectionInfo {
+                id: their_id,
+                for_direct: vec![],
+            };
 
-            let (event_tx, _event_rx) = get_event_sender();
-            unwrap!(Connect::start(
+            let (event_tx, event_rx) = get_event_sender();
+            match Connect::start(
                 &mut core,
                 &poll,
                 our_ci,
-                their_ci.clone(),
+                their_ci,
                 [1; 32],
                 event_tx,
                 &our_sk,
                 Default::default(),
-            ));
+            ) {
+                Err(CrustError::InsufficientConnectionInfo) => (),
+                res => panic!("Unexpected result: {:?}", res),
+            }
 
-            let connect_state_token = Token(0);
-            let state = unwrap!(core.get_state(connect_state_token));
-            let mut state = state.borrow_mut();
-            let connect_state = unwrap!(state.as_any().downcast_mut::<Connect>());
+            match unwrap!(event_rx.try_recv()) {
+                Event::ConnectFailure(id) => assert_eq!(id, their_id),
+                event => panic!("Unexpected event: {:?}", event),
+            }
+            assert!(core.get_state(CONNECT_STATE_TOKEN).is_none());
+        }
+
+        // Other platforms may report the failed dial asynchronously.
+        #[cfg(target_os = "linux")]
+        #[test]
+        fn it_fails_immediately_when_all_dials_fail_right_away() {
+            let mut core = test_core(test_bootstrap_cache());
+            let poll = unwrap!(Poll::new());
+
+            // Linux rejects TCP connections to multicast addresses straight away.
+            let (their_id, event_rx) =
+                start_connect(&mut core, &poll, vec![ipv4_addr(224, 0, 0, 1, 4000)]);
 
-            connect_state.remove_peer_from_cache(&mut core, &cached_peer);
+            match unwrap!(event_rx.try_recv()) {
+                Event::ConnectFailure(id) => assert_eq!(id, their_id),
+                event => panic!("Unexpected event: {:?}", event),
+            }
+            assert!(core.get_state(CONNECT_STATE_TOKEN).is_none());
+            assert!(!core.user_data().connections.contains_key(&their_id));
+        }
+
+        #[cfg(target_os = "linux")]
+        #[test]
+        fn it_keeps_peer_cached_when_dial_fails_right_away() {
+            let cached_peer = peer_info_with_rand_key(ipv4_addr(224, 0, 0, 1, 4000));
+            let mut bootstrap_cache = test_bootstrap_cache();
+            let _ = bootstrap_cache.put(cached_peer);
+            let mut core = test_core(bootstrap_cache);
+            let poll = unwrap!(Poll::new());
+
+            let _ = start_connect(&mut core, &poll, vec![cached_peer.addr]);
 
             let cached_peers = core.user_data().bootstrap_cache.snapshot();
-            assert!(cached_peers.is_empty());
+            assert_eq!(cached_peers.len(), 1);
+        }
+
+        #[test]
+        fn it_dials_at_most_max_direct_addrs() {
+            let mut core = test_core(test_bootstrap_cache());
+            core.user_data_mut().config.cfg.max_direct_addrs = Some(2);
+            let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(5);
+            let _ = start_connect(&mut core, &poll, addrs);
+
+            with_connect_state(&mut core, |connect_state, _| {
+                assert_eq!(connect_state.children.len(), 2);
+            });
+        }
+
+        #[test]
+        fn it_dials_at_most_max_concurrent_connects_at_a_time() {
+            let mut core = test_core(test_bootstrap_cache());
+            core.user_data_mut().config.cfg.max_concurrent_connects = Some(2);
+            let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(5);
+            let (their_id, _event_rx) = start_connect(&mut core, &poll, addrs.clone());
+
+            with_connect_state(&mut core, |connect_state, core| {
+                assert_eq!(connect_state.children.len(), 2);
+                assert_eq!(connect_state.queued.len(), 3);
+
+                let child = *unwrap!(connect_state.children.iter().next());
+                let failed_peer = PeerInfo::new(addrs[0], their_id.pub_enc_key);
+                connect_state.handle_exchange_msg(
+                    core,
+                    &poll,
+                    child,
+                    None,
+                    failed_peer,
+                    0,
+                    Instant::now(),
+                );
+                assert_eq!(connect_state.children.len(), 2);
+                assert_eq!(connect_state.queued.len(), 2);
+            });
+        }
+
+        #[test]
+        fn it_dials_one_address_at_a_time_if_max_concurrent_connects_is_zero() {
+            let mut core = test_core(test_bootstrap_cache());
+            core.user_data_mut().config.cfg.max_concurrent_connects = Some(0);
+            let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(3);
+            let _ = start_connect(&mut core, &poll, addrs);
+
+            with_connect_state(&mut core, |connect_state, _| {
+                assert_eq!(connect_state.children.len(), 1);
+                assert_eq!(connect_state.queued.len(), 2);
+            });
+        }
+
+        #[test]
+        fn it_dials_duplicate_addresses_once() {
+            let mut core = test_core(test_bootstrap_cache());
+            let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(2);
+            let addrs = vec![addrs[0], addrs[1], addrs[0], addrs[1], addrs[0]];
+            let _ = start_connect(&mut core, &poll, addrs);
+
+            with_connect_state(&mut core, |connect_state, _| {
+                assert_eq!(connect_state.children.len(), 2);
+                assert!(connect_state.queued.is_empty());
+            });
+        }
+
+        #[test]
+        fn failed_attempt_is_retried_until_retries_run_out() {
+            let mut core = test_core(test_bootstrap_cache());
+            core.user_data_mut().config.cfg.connect_retries = Some(1);
+            let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(1);
+            let (their_id, _event_rx) = start_connect(&mut core, &poll, addrs.clone());
+            let peer_info = PeerInfo::new(addrs[0], their_id.pub_enc_key);
+
+            with_connect_state(&mut core, |connect_state, core| {
+                let child = *unwrap!(connect_state.children.iter().next());
+                connect_state.handle_exchange_msg(
+                    core,
+                    &poll,
+                    child,
+                    None,
+                    peer_info,
+                    0,
+                    Instant::now(),
+                );
+                assert!(connect_state.children.is_empty());
+                assert_eq!(connect_state.pending_retries.len(), 1);
+
+                let timer_id = *unwrap!(connect_state.pending_retries.keys().next());
+                State::timeout(connect_state, core, &poll, timer_id);
+                assert!(connect_state.pending_retries.is_empty());
+                assert_eq!(connect_state.children.len(), 1);
+
+                let child = *unwrap!(connect_state.children.iter().next());
+                connect_state.handle_exchange_msg(
+                    core,
+                    &poll,
+                    child,
+                    None,
+                    peer_info,
+                    1,
+                    Instant::now(),
+                );
+                assert!(connect_state.pending_retries.is_empty());
+                assert!(core.get_state(CONNECT_STATE_TOKEN).is_none());
+            });
+        }
+
+        #[test]
+        fn failed_attempt_is_not_retried_by_default() {
+            let mut core = test_core(test_bootstrap_cache());
+            let poll = unwrap!(Poll::new());
+            let (_listeners, addrs) = local_listeners(1);
+            let (their_id, _event_rx) = start_connect(&mut core, &poll, addrs.clone());
+            let peer_info = PeerInfo::new(addrs[0], their_id.pub_enc_key);
+
+            with_connect_state(&mut core, |connect_state, core| {
+                let child = *unwrap!(connect_state.children.iter().next());
+                connect_state.handle_exchange_msg(
+                    core,
+                    &poll,
+                    child,
+                    None,
+                    peer_info,
+                    0,
+                    Instant::now(),
+                );
+                assert!(connect_state.pending_retries.is_empty());
+                assert!(core.get_state(CONNECT_STATE_TOKEN).is_none());
+            });
+        }
+    }
+
+    mod retry_policy {
+        use super::*;
+
+        #[test]
+        fn delay_grows_by_backoff_factor() {
+            let retry = RetryPolicy {
+                max_retries: 3,
+                delay_ms: 200,
+                backoff: 2,
+            };
+
+            assert_eq!(retry.delay(0), Duration::from_millis(200));
+            assert_eq!(retry.delay(1), Duration::from_millis(400));
+            assert_eq!(retry.delay(2), Duration::from_millis(800));
+        }
+
+        #[test]
+        fn delay_saturates_instead_of_overflowing() {
+            let retry = RetryPolicy {
+                max_retries: 100,
+                delay_ms: 200,
+                backoff: 10,
+            };
+
+            assert_eq!(retry.delay(100), Duration::from_millis(u64::max_value()));
+        }
+    }
+
+    mod is_network_available {
+        use super::*;
+        use crate::common::ipv4_addr;
+        use get_if_addrs::{IfAddr, Ifv4Addr, Ifv6Addr};
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        fn ifv4(ip: Ipv4Addr) -> Interface {
+            Interface {
+                name: "test".to_owned(),
+                addr: IfAddr::V4(Ifv4Addr {
+                    ip,
+                    netmask: Ipv4Addr::new(255, 255, 255, 0),
+                    broadcast: None,
+                }),
+            }
+        }
+
+        fn ifv6(ip: Ipv6Addr) -> Interface {
+            Interface {
+                name: "test".to_owned(),
+                addr: IfAddr::V6(Ifv6Addr {
+                    ip,
+                    netmask: Ipv6Addr::new(0xffff, 0xffff, 0xffff, 0xffff, 0, 0, 0, 0),
+                    broadcast: None,
+                }),
+            }
+        }
+
+        #[test]
+        fn when_host_has_only_loopback_it_returns_false() {
+            let interfaces = vec![ifv4(Ipv4Addr::new(127, 0, 0, 1))];
+
+            assert!(!is_network_available(
+                &interfaces,
+                &[ipv4_addr(1, 2, 3, 4, 4000)]
+            ));
+        }
+
+        #[test]
+        fn when_host_has_only_loopback_it_still_allows_loopback_peers() {
+            let interfaces = vec![ifv4(Ipv4Addr::new(127, 0, 0, 1))];
+
+            assert!(is_network_available(
+                &interfaces,
+                &[ipv4_addr(127, 0, 0, 1, 4000)]
+            ));
+        }
+
+        #[test]
+        fn when_address_family_does_not_match_it_returns_false() {
+            let interfaces = vec![ifv4(Ipv4Addr::new(192, 168, 0, 2))];
+            let addr = unwrap!("[2001:db8::1]:4000".parse());
+
+            assert!(!is_network_available(&interfaces, &[addr]));
+        }
+
+        #[test]
+        fn when_host_has_only_link_local_addresses_it_returns_false() {
+            let interfaces = vec![
+                ifv4(Ipv4Addr::new(169, 254, 1, 1)),
+                ifv6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+            ];
+            let v4_addr = ipv4_addr(1, 2, 3, 4, 4000);
+            let v6_addr = unwrap!("[2001:db8::1]:4000".parse());
+
+            assert!(!is_network_available(&interfaces, &[v4_addr, v6_addr]));
+        }
+
+        #[test]
+        fn when_host_has_global_ipv6_address_it_allows_ipv6_peers() {
+            let interfaces = vec![
+                ifv6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+                ifv6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)),
+            ];
+            let addr = unwrap!("[2001:db8::1]:4000".parse());
+
+            assert!(is_network_available(&interfaces, &[addr]));
+        }
+
+        #[test]
+        fn when_non_loopback_interface_is_up_it_returns_true() {
+            let interfaces = vec![
+                ifv4(Ipv4Addr::new(127, 0, 0, 1)),
+                ifv4(Ipv4Addr::new(192, 168, 0, 2)),
+            ];
+
+            assert!(is_network_available(
+                &interfaces,
+                &[ipv4_addr(1, 2, 3, 4, 4000)]
+            ));
+        }
+    }
+
+    mod connect_tcp {
+        use super::*;
+        use crate::common::ipv4_addr;
+        use std::net::TcpListener;
+
+        fn free_port() -> u16 {
+            let listener = unwrap!(TcpListener::bind("127.0.0.1:0"));
+            unwrap!(listener.local_addr()).port()
+        }
+
+        #[test]
+        fn it_binds_to_given_local_address() {
+            let listener = unwrap!(TcpListener::bind("127.0.0.1:0"));
+            let listener_addr = unwrap!(listener.local_addr());
+            let bind_addr = ipv4_addr(127, 0, 0, 1, free_port());
+
+            let _socket = unwrap!(connect_tcp(&listener_addr, Some(bind_addr), None));
+
+            let (_stream, peer_addr) = unwrap!(listener.accept());
+            assert_eq!(peer_addr, bind_addr);
+        }
+
+        #[test]
+        fn it_binds_to_given_port_on_any_ip() {
+            let listener = unwrap!(TcpListener::bind("127.0.0.1:0"));
+            let listener_addr = unwrap!(listener.local_addr());
+            let source_port = free_port();
+
+            let _socket = unwrap!(connect_tcp(
+                &listener_addr,
+                Some(ipv4_addr(0, 0, 0, 0, source_port)),
+                None
+            ));
+
+            let (_stream, peer_addr) = unwrap!(listener.accept());
+            assert_eq!(peer_addr.port(), source_port);
+        }
+
+        #[test]
+        fn when_bind_port_is_in_use_it_falls_back_to_ephemeral_port() {
+            let listener = unwrap!(TcpListener::bind("127.0.0.1:0"));
+            let listener_addr = unwrap!(listener.local_addr());
+            let busy_port = listener_addr.port();
+
+            let _socket = unwrap!(connect_tcp(
+                &listener_addr,
+                Some(ipv4_addr(0, 0, 0, 0, busy_port)),
+                None
+            ));
+
+            let (_stream, peer_addr) = unwrap!(listener.accept());
+            assert_ne!(peer_addr.port(), busy_port);
+        }
+
+        #[test]
+        fn when_bind_address_family_differs_it_is_ignored() {
+            let listener = unwrap!(TcpListener::bind("127.0.0.1:0"));
+            let listener_addr = unwrap!(listener.local_addr());
+            let bind_addr = unwrap!(format!("[::1]:{}", free_port()).parse());
+
+            let _socket = unwrap!(connect_tcp(&listener_addr, Some(bind_addr), None));
+
+            let (_stream, peer_addr) = unwrap!(listener.accept());
+            assert!(peer_addr.is_ipv4());
+        }
+
+        #[test]
+        fn it_sets_given_ttl() {
+            let listener = unwrap!(TcpListener::bind("127.0.0.1:0"));
+            let listener_addr = unwrap!(listener.local_addr());
+
+            let stream = unwrap!(connect_tcp(&listener_addr, None, Some(42)));
+            assert_eq!(unwrap!(stream.ttl()), 42);
+
+            let bind_addr = ipv4_addr(127, 0, 0, 1, free_port());
+            let stream = unwrap!(connect_tcp(&listener_addr, Some(bind_addr), Some(43)));
+            assert_eq!(unwrap!(stream.ttl()), 43);
         }
     }
 }
@@ -7,7 +7,7 @@
 // specific language governing permissions and limitations relating to use of the SAFE Network
 // Software.
 
-use crate::common::{Message, State};
+use crate::common::{Message, State, HANDSHAKE_PRIORITY};
 use crate::main::{ConnectionId, CrustData, EventLoopCore};
 use crate::PeerId;
 use mio::{Poll, PollOpt, Ready, Token};
@@ -45,7 +45,7 @@ impl ConnectionCandidate {
             socket,
             our_id,
             their_id,
-            msg: Some((Message::ChooseConnection, 0)),
+            msg: Some((Message::ChooseConnection, HANDSHAKE_PRIORITY)),
             finish,
         }));
 
@@ -8,8 +8,8 @@
 // Software.
 
 use crate::common::{
-    ipv4_addr, BootstrapDenyReason, BootstrapperRole, CoreTimer, CrustUser, Message, NameHash,
-    PeerInfo, State,
+    ipv4_addr, is_accepted_name_hash, BootstrapDenyReason, BootstrapperRole, CoreTimer, CrustUser,
+    Message, NameHash, PeerInfo, State, HANDSHAKE_PRIORITY,
 };
 use crate::main::{
     read_config_file, ActiveConnection, Config, ConnectionCandidate, ConnectionId, CrustData,
@@ -28,7 +28,7 @@ use std::collections::HashSet;
 use std::mem;
 use std::net::SocketAddr;
 use std::rc::{Rc, Weak};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub const EXCHANGE_MSG_TIMEOUT_SEC: u64 = 10 * 60;
 const CHECK_REACHABILITY_TIMEOUT_SEC: u64 = 3;
@@ -54,6 +54,8 @@ pub struct ExchangeMsg {
     test_ext_reachability: bool,
     self_weak: Weak<RefCell<ExchangeMsg>>,
     our_sk: SecretEncryptKey,
+    /// When the peer connected to us.
+    started_at: Instant,
 }
 
 impl ExchangeMsg {
@@ -96,6 +98,7 @@ impl ExchangeMsg {
             test_ext_reachability,
             self_weak: Default::default(),
             our_sk: our_sk.clone(),
+            started_at: Instant::now(),
         }));
 
         state.borrow_mut().self_weak = Rc::downgrade(&state);
@@ -151,8 +154,11 @@ impl ExchangeMsg {
         name_hash: NameHash,
         their_role: BootstrapperRole,
     ) {
-        if !self.is_valid_name_hash(name_hash) {
-            debug!("Rejecting Bootstrapper with an invalid name hash.");
+        if !self.is_valid_name_hash(name_hash, &core.user_data().config.cfg) {
+            debug!(
+                "Rejecting Bootstrapper with an invalid name hash: {:?}",
+                name_hash
+            );
             return self.write(
                 core,
                 poll,
@@ -176,7 +182,11 @@ impl ExchangeMsg {
                 BootstrapperRole::Node(_) => BootstrapDenyReason::NodeNotWhitelisted,
                 BootstrapperRole::Client => BootstrapDenyReason::ClientNotWhitelisted,
             };
-            return self.write(core, poll, Some((Message::BootstrapDenied(reason), 0)));
+            return self.write(
+                core,
+                poll,
+                Some((Message::BootstrapDenied(reason), HANDSHAKE_PRIORITY)),
+            );
         }
 
         if let BootstrapperRole::Node(their_addrs) = their_role {
@@ -202,7 +212,11 @@ impl ExchangeMsg {
                          reachability. Denying bootstrap."
                     );
                     let reason = BootstrapDenyReason::FailedExternalReachability;
-                    self.write(core, poll, Some((Message::BootstrapDenied(reason), 0)));
+                    self.write(
+                        core,
+                        poll,
+                        Some((Message::BootstrapDenied(reason), HANDSHAKE_PRIORITY)),
+                    );
                 }
             } else {
                 self.send_bootstrap_grant(core, poll, their_uid, CrustUser::Node)
@@ -260,7 +274,11 @@ impl ExchangeMsg {
                  Denying bootstrap."
             );
             let reason = BootstrapDenyReason::FailedExternalReachability;
-            self.write(core, poll, Some((Message::BootstrapDenied(reason), 0)));
+            self.write(
+                core,
+                poll,
+                Some((Message::BootstrapDenied(reason), HANDSHAKE_PRIORITY)),
+            );
         }
     }
 
@@ -275,7 +293,11 @@ impl ExchangeMsg {
 
         let our_uid = self.our_uid;
         self.next_state = NextState::ActiveConnection(their_uid, peer_kind);
-        self.write(core, poll, Some((Message::BootstrapGranted(our_uid), 0)))
+        self.write(
+            core,
+            poll,
+            Some((Message::BootstrapGranted(our_uid), HANDSHAKE_PRIORITY)),
+        )
     }
 
     fn handle_connect(
@@ -286,8 +308,11 @@ impl ExchangeMsg {
         name_hash: NameHash,
         their_addrs: HashSet<SocketAddr>,
     ) {
-        if !self.is_valid_name_hash(name_hash) {
-            debug!("Invalid name hash given. Denying connection.");
+        if !self.is_valid_name_hash(name_hash, &core.user_data().config.cfg) {
+            debug!(
+                "Invalid name hash given: {:?}. Denying connection.",
+                name_hash
+            );
             return self.terminate(core, poll);
         }
 
@@ -298,6 +323,11 @@ impl ExchangeMsg {
             return self.terminate(core, poll);
         }
 
+        if !self.is_peer_allowed_by_connect_filter(core) {
+            debug!("Connecting Node is rejected by the connect filter. Denying connection.");
+            return self.terminate(core, poll);
+        }
+
         if !self.use_authed_encryption(their_uid.pub_enc_key) {
             debug!("Failed to set authenticated encryption context.");
             return self.terminate(core, poll);
@@ -322,7 +352,11 @@ impl ExchangeMsg {
             if self.reachability_children.is_empty() {
                 debug!("External reachability test failed. Denying connect request.");
                 let reason = BootstrapDenyReason::FailedExternalReachability;
-                self.write(core, poll, Some((Message::BootstrapDenied(reason), 0)));
+                self.write(
+                    core,
+                    poll,
+                    Some((Message::BootstrapDenied(reason), HANDSHAKE_PRIORITY)),
+                );
             }
         } else {
             self.send_connect_grant(core, poll, their_uid);
@@ -353,7 +387,7 @@ impl ExchangeMsg {
         self.enter_handshaking_mode(core, their_uid);
         self.next_state = NextState::ConnectionCandidate(their_uid);
         let msg = Message::ConnectResponse(self.our_uid, self.name_hash);
-        self.write(core, poll, Some((msg, 0)));
+        self.write(core, poll, Some((msg, HANDSHAKE_PRIORITY)));
     }
 
     fn handle_echo_addr_req(
@@ -368,7 +402,11 @@ impl ExchangeMsg {
             self.socket.peer_addr(),
         ) {
             (true, Ok(peer_addr)) => {
-                self.write(core, poll, Some((Message::EchoAddrResp(peer_addr), 0)));
+                self.write(
+                    core,
+                    poll,
+                    Some((Message::EchoAddrResp(peer_addr), HANDSHAKE_PRIORITY)),
+                );
             }
             _ => self.terminate(core, poll),
         }
@@ -466,8 +504,25 @@ impl ExchangeMsg {
         );
     }
 
-    fn is_valid_name_hash(&self, name_hash: NameHash) -> bool {
-        self.name_hash == name_hash
+    fn is_peer_allowed_by_connect_filter(&self, core: &EventLoopCore) -> bool {
+        let connect_filter = match core.user_data().connect_filter {
+            Some(ref connect_filter) => connect_filter,
+            None => return true,
+        };
+        match self.socket.peer_addr() {
+            Ok(peer_addr) => connect_filter(peer_addr),
+            Err(e) => {
+                debug!(
+                    "Could not obtain address of peer: {:?}. Denying handshake.",
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    fn is_valid_name_hash(&self, name_hash: NameHash, config: &Config) -> bool {
+        is_accepted_name_hash(&name_hash, &self.name_hash, &config.accepted_network_names)
     }
 
     fn validate_peer_uid(&self, their_uid: PeerId) -> Result<PeerId, ()> {
@@ -520,6 +575,7 @@ impl ExchangeMsg {
 
         let our_uid = self.our_uid;
         let event_tx = self.event_tx.clone();
+        let started_at = self.started_at;
 
         match self.next_state {
             NextState::ActiveConnection(their_uid, peer_kind) => {
@@ -534,6 +590,7 @@ impl ExchangeMsg {
                     peer_kind,
                     Event::BootstrapAccept(their_uid, peer_kind),
                     event_tx,
+                    started_at,
                 );
             }
             NextState::ConnectionCandidate(their_uid) => {
@@ -551,6 +608,7 @@ impl ExchangeMsg {
                             CrustUser::Node,
                             Event::ConnectSuccess(their_uid),
                             event_tx.clone(),
+                            started_at,
                         );
                     }
                 };
@@ -479,6 +479,19 @@ mod tests {
         connect(NAME_HASH, uid, &our_sk, &listener);
     }
 
+    #[test]
+    fn connect_with_hash_of_accepted_network_name() {
+        let listener = start_listener(false);
+        unwrap!(listener
+            ._el
+            .send(CoreMessage::new(|core: &mut EventLoopCore, _| {
+                core.user_data_mut().config.cfg.accepted_network_names =
+                    Some(vec!["old-network".to_owned()]);
+            })));
+        let (uid, our_sk) = rand_peer_id_and_enc_sk();
+        connect(safe_crypto::hash(b"old-network"), uid, &our_sk, &listener);
+    }
+
     #[test]
     #[should_panic]
     fn connect_to_self() {
@@ -503,6 +516,19 @@ mod tests {
         connect(NAME_HASH_2, uid, &our_sk, &listener);
     }
 
+    #[test]
+    #[should_panic]
+    fn connect_rejected_by_connect_filter() {
+        let listener = start_listener(true);
+        unwrap!(listener
+            ._el
+            .send(CoreMessage::new(|core: &mut EventLoopCore, _| {
+                core.user_data_mut().connect_filter = Some(Box::new(|_| false));
+            })));
+        let (uid, our_sk) = rand_peer_id_and_enc_sk();
+        connect(NAME_HASH, uid, &our_sk, &listener);
+    }
+
     #[test]
     #[should_panic]
     fn bootstrap_with_invalid_pub_key() {
@@ -54,6 +54,10 @@ quick_error! {
         InsufficientConnectionInfo {
             description("Not enough information to initiate connection to peer")
         }
+        /// No network interface is up that could reach the peer
+        NoNetwork {
+            description("No network interface is up that could reach the peer")
+        }
         /// Nat Traversal errors
         Nat(e: nat::NatError) {
             description("Nat Traversal module error")
@@ -25,6 +25,7 @@ use socket_collection::Priority;
 use std::collections::HashSet;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
 const SERVICE_DISCOVERY_DEFAULT_PORT: u16 = 5484;
 
@@ -155,6 +156,20 @@ impl Service {
         Ok(service)
     }
 
+    /// Sets a filter which decides what peer addresses we connect to. Addresses given to
+    /// [`connect`] are dropped and incoming connect requests are denied if the filter rejects
+    /// them. This complements `whitelisted_node_ips` for addresses that can't be listed up front.
+    ///
+    /// [`connect`]: #method.connect
+    pub fn set_connect_filter<F>(&self, filter: F) -> crate::Res<()>
+    where
+        F: Fn(SocketAddr) -> bool + Send + 'static,
+    {
+        self.post(move |core, _| {
+            core.user_data_mut().connect_filter = Some(Box::new(filter));
+        })
+    }
+
     /// Allow (or disallow) peers from bootstrapping off us.
     pub fn set_accept_bootstrap(&self, accept: bool) -> crate::Res<()> {
         let (tx, rx) = mpsc::channel();
@@ -265,55 +280,16 @@ impl Service {
     /// Checks if given peer was connected, if so, returns it's address together with a flag
     /// indicating whether it was hard coded in config or not.
     fn get_peer_socket_addr(&self, peer_uid: &PeerId) -> crate::Res<(SocketAddr, bool)> {
-        let peer_uid = *peer_uid;
-        let (tx, rx) = mpsc::channel();
-
-        let _ = self.post(move |core, _| {
-            let token = match core.user_data().connections.get(&peer_uid) {
-                Some(&ConnectionId {
-                    active_connection: Some(token),
-                    ..
-                }) => token,
-                _ => {
-                    let _ = tx.send(None);
-                    return;
-                }
-            };
-            let state = match core.get_state(token) {
-                Some(state) => state,
-                None => {
-                    let _ = tx.send(None);
-                    return;
-                }
-            };
-            match state
-                .borrow_mut()
-                .as_any()
-                .downcast_mut::<ActiveConnection>()
-            {
-                Some(active_connection) => {
-                    let config = &core.user_data().config.cfg;
-                    let peer_addr_res = active_connection.peer_addr().map(|peer_addr| {
-                        let was_hard_coded = config
-                            .hard_coded_contacts
-                            .iter()
-                            .any(|peer| peer.addr.ip() == peer_addr.ip());
-                        (peer_addr, was_hard_coded)
-                    });
-                    let _ = tx.send(Some(peer_addr_res));
-                }
-                None => {
-                    debug!("Expected token {:?} to be ActiveConnection", token);
-                    let _ = tx.send(None);
-                }
-            };
-        });
-
-        match rx.recv() {
-            Ok(Some(ip)) => ip,
-            Ok(None) => Err(CrustError::PeerNotFound),
-            Err(e) => Err(CrustError::ChannelRecv(e)),
-        }
+        self.with_active_connection(peer_uid, |active_connection, config| {
+            active_connection.peer_addr().map(|peer_addr| {
+                let was_hard_coded = config
+                    .hard_coded_contacts
+                    .iter()
+                    .any(|peer| peer.addr.ip() == peer_addr.ip());
+                (peer_addr, was_hard_coded)
+            })
+        })
+        .and_then(|res| res)
     }
 
     /// Return the ip address of the peer.
@@ -321,6 +297,31 @@ impl Service {
         self.get_peer_socket_addr(peer_uid).map(|(s, _)| s.ip())
     }
 
+    /// Returns when we last queued data for the given peer or received data from them. Heartbeats
+    /// don't count.
+    pub fn last_activity(&self, peer_uid: &PeerId) -> crate::Res<Instant> {
+        self.with_active_connection(peer_uid, |active_connection, _| {
+            active_connection.last_activity()
+        })
+    }
+
+    /// Returns our local socket address of the connection to the given peer.
+    pub fn local_addr_for_peer(&self, peer_uid: &PeerId) -> crate::Res<SocketAddr> {
+        self.with_active_connection(peer_uid, |active_connection, _| {
+            active_connection.local_addr()
+        })
+        .and_then(|res| res)
+    }
+
+    /// Returns how long the handshake of our connection to the given peer took. For connections
+    /// we initiated, that's from dialing the peer until the connection was established. For
+    /// incoming connections, it's from accepting the connection.
+    pub fn handshake_duration(&self, peer_uid: &PeerId) -> crate::Res<Duration> {
+        self.with_active_connection(peer_uid, |active_connection, _| {
+            active_connection.handshake_duration()
+        })
+    }
+
     /// Returns whether the given peer's IP is in the config file's hard-coded contacts list.
     pub fn is_peer_hard_coded(&self, peer_uid: &PeerId) -> bool {
         self.get_peer_socket_addr(peer_uid)
@@ -332,7 +333,6 @@ impl Service {
     /// Check if we have peers on LAN
     pub fn has_peers_on_lan(&self) -> bool {
         use std::thread;
-        use std::time::Duration;
 
         let (obs, rx) = mpsc::channel();
         let _ = self.post(move |core, _| {
@@ -477,6 +477,9 @@ impl Service {
                     .collect();
                 their_ci.for_direct = their_direct;
             }
+            if let Some(ref connect_filter) = core.user_data().connect_filter {
+                their_ci.for_direct.retain(|addr| connect_filter(*addr));
+            }
 
             if core.user_data().connections.contains_key(&their_ci.id) {
                 debug!(
@@ -485,7 +488,8 @@ impl Service {
                 );
                 return;
             }
-            let _ = Connect::start(
+            let their_id = their_ci.id;
+            if let Err(e) = Connect::start(
                 core,
                 poll,
                 our_ci,
@@ -494,7 +498,9 @@ impl Service {
                 event_tx,
                 &our_sk,
                 our_global_listener_addrs(core),
-            );
+            ) {
+                info!("Failed to connect to {:?}: {}", their_id, e);
+            }
         })?;
 
         Ok(())
@@ -524,6 +530,9 @@ impl Service {
     }
 
     /// Send data to a peer.
+    ///
+    /// Messages with lower `priority` values are sent first. 0 is the highest priority, which is
+    /// also what crust uses for its own handshake messages.
     pub fn send(&self, peer_uid: &PeerId, msg: Vec<u8>, priority: Priority) -> crate::Res<()> {
         let peer_uid = *peer_uid;
         let (tx, rx) = mpsc::channel();
@@ -665,6 +674,40 @@ impl Service {
         rx.recv().map_err(CrustError::ChannelRecv)
     }
 
+    /// Calls the given function with the active connection to the given peer and our config on the
+    /// event loop thread and returns its result.
+    fn with_active_connection<F, R>(&self, peer_uid: &PeerId, f: F) -> crate::Res<R>
+    where
+        F: FnOnce(&mut ActiveConnection, &Config) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let peer_uid = *peer_uid;
+        let (tx, rx) = mpsc::channel();
+
+        let _ = self.post(move |core, _| {
+            let res = match core.user_data().connections.get(&peer_uid) {
+                Some(&ConnectionId {
+                    active_connection: Some(token),
+                    ..
+                }) => core.get_state(token).and_then(|state| {
+                    state
+                        .borrow_mut()
+                        .as_any()
+                        .downcast_mut::<ActiveConnection>()
+                        .map(|active_connection| f(active_connection, &core.user_data().config.cfg))
+                }),
+                _ => None,
+            };
+            let _ = tx.send(res);
+        });
+
+        match rx.recv() {
+            Ok(Some(res)) => Ok(res),
+            Ok(None) => Err(CrustError::PeerNotFound),
+            Err(e) => Err(CrustError::ChannelRecv(e)),
+        }
+    }
+
     fn post<F>(&self, f: F) -> crate::Res<()>
     where
         F: FnOnce(&mut EventLoopCore, &Poll) + Send + 'static,
@@ -766,27 +809,118 @@ mod tests {
     #[test]
     fn direct_connect_two_peers() {
         timebomb(Duration::from_secs(30), || {
-            let (event_tx_0, event_rx_0) = get_event_sender();
-            let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
-            let mut service_0 = unwrap!(Service::try_new(event_tx_0, peer_id, peer_sk));
+            let (service_0, event_rx_0, service_1, event_rx_1) = connected_services();
+            exchange_messages(&service_0, &event_rx_0, &service_1, &event_rx_1);
+        })
+    }
 
-            unwrap!(service_0.start_listening_tcp());
-            expect_event!(event_rx_0, Event::ListenerStarted(_));
-            unwrap!(service_0.set_ext_reachability_test(false));
+    #[test]
+    fn last_activity_advances_on_send_and_receive() {
+        timebomb(Duration::from_secs(30), || {
+            let (service_0, _event_rx_0, service_1, event_rx_1) = connected_services();
+            let id_0 = service_0.id();
+            let id_1 = service_1.id();
 
-            let (event_tx_1, event_rx_1) = get_event_sender();
-            let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
-            let mut service_1 = unwrap!(Service::try_new(event_tx_1, peer_id, peer_sk));
+            let sender_activity = unwrap!(service_0.last_activity(&id_1));
+            let receiver_activity = unwrap!(service_1.last_activity(&id_0));
+            thread::sleep(Duration::from_millis(50));
 
-            unwrap!(service_1.start_listening_tcp());
-            expect_event!(event_rx_1, Event::ListenerStarted(_));
-            unwrap!(service_1.set_ext_reachability_test(false));
+            unwrap!(service_0.send(&id_1, vec![1, 2, 3], 0));
+            assert!(unwrap!(service_0.last_activity(&id_1)) > sender_activity);
 
-            connect(&service_0, &event_rx_0, &service_1, &event_rx_1);
-            exchange_messages(&service_0, &event_rx_0, &service_1, &event_rx_1);
+            expect_event!(event_rx_1, Event::NewMessage(id, CrustUser::Node, _) => {
+                assert_eq!(id, id_0)
+            });
+            assert!(unwrap!(service_1.last_activity(&id_0)) > receiver_activity);
+        })
+    }
+
+    #[test]
+    fn last_activity_does_not_advance_on_heartbeats() {
+        use crate::main::INACTIVITY_TIMEOUT_MS;
+
+        timebomb(Duration::from_secs(30), || {
+            let (service_0, _event_rx_0, service_1, _event_rx_1) = connected_services();
+            let id_0 = service_0.id();
+            let id_1 = service_1.id();
+
+            let activity_0 = unwrap!(service_0.last_activity(&id_1));
+            let activity_1 = unwrap!(service_1.last_activity(&id_0));
+            // Several heartbeat periods, so both sides send and receive heartbeats meanwhile.
+            thread::sleep(Duration::from_millis(2 * INACTIVITY_TIMEOUT_MS));
+
+            assert_eq!(unwrap!(service_0.last_activity(&id_1)), activity_0);
+            assert_eq!(unwrap!(service_1.last_activity(&id_0)), activity_1);
+        })
+    }
+
+    #[test]
+    fn local_addr_for_peer_returns_bound_address() {
+        timebomb(Duration::from_secs(30), || {
+            let (service_0, _event_rx_0, service_1, _event_rx_1) = connected_services();
+
+            let local_addr = unwrap!(service_0.local_addr_for_peer(&service_1.id()));
+            assert!(!local_addr.ip().is_unspecified());
+            assert_ne!(local_addr.port(), 0);
+        })
+    }
+
+    #[test]
+    fn handshake_duration_is_recorded_on_both_sides() {
+        timebomb(Duration::from_secs(30), || {
+            let connect_started = Instant::now();
+            let (service_0, _event_rx_0, service_1, _event_rx_1) = connected_services();
+            let max_duration = connect_started.elapsed();
+
+            let duration_0 = unwrap!(service_0.handshake_duration(&service_1.id()));
+            let duration_1 = unwrap!(service_1.handshake_duration(&service_0.id()));
+            assert!(duration_0 <= max_duration);
+            assert!(duration_1 <= max_duration);
+
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(
+                unwrap!(service_0.handshake_duration(&service_1.id())),
+                duration_0
+            );
+        })
+    }
+
+    #[test]
+    fn connect_fails_when_connect_filter_rejects_all_addresses() {
+        timebomb(Duration::from_secs(30), || {
+            let (event_tx, event_rx) = get_event_sender();
+            let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
+            let mut service = unwrap!(Service::try_new(event_tx, peer_id, peer_sk));
+            unwrap!(service.start_listening_tcp());
+            expect_event!(event_rx, Event::ListenerStarted(_));
+            unwrap!(service.set_connect_filter(|_| false));
+
+            let our_ci = prepare_connection_info(&mut service, &event_rx);
+            let listener = unwrap!(std::net::TcpListener::bind("127.0.0.1:0"));
+            let (their_id, _) = rand_peer_id_and_enc_sk();
+            let their_ci = PubConnectionInfo {
+                id: their_id,
+                for_direct: vec![unwrap!(listener.local_addr())],
+            };
+            unwrap!(service.connect(our_ci, their_ci));
+
+            expect_event!(event_rx, Event::ConnectFailure(id) => assert_eq!(id, their_id));
         })
     }
 
+    #[test]
+    fn last_activity_of_unknown_peer_is_an_error() {
+        let (event_tx, _event_rx) = get_event_sender();
+        let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
+        let service = unwrap!(Service::try_new(event_tx, peer_id, peer_sk));
+        let (unknown_peer_id, _) = rand_peer_id_and_enc_sk();
+
+        match service.last_activity(&unknown_peer_id) {
+            Err(CrustError::PeerNotFound) => (),
+            res => panic!("Expected CrustError::PeerNotFound, got {:?}", res),
+        }
+    }
+
     #[test]
     #[ignore]
     fn rendezvous_connect_two_peers() {
@@ -807,6 +941,28 @@ mod tests {
         thread::sleep(Duration::from_secs(1));
     }
 
+    /// Starts two listening services and connects them to each other.
+    fn connected_services() -> (Service, Receiver<Event>, Service, Receiver<Event>) {
+        let (event_tx_0, event_rx_0) = get_event_sender();
+        let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
+        let mut service_0 = unwrap!(Service::try_new(event_tx_0, peer_id, peer_sk));
+
+        unwrap!(service_0.start_listening_tcp());
+        expect_event!(event_rx_0, Event::ListenerStarted(_));
+        unwrap!(service_0.set_ext_reachability_test(false));
+
+        let (event_tx_1, event_rx_1) = get_event_sender();
+        let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
+        let mut service_1 = unwrap!(Service::try_new(event_tx_1, peer_id, peer_sk));
+
+        unwrap!(service_1.start_listening_tcp());
+        expect_event!(event_rx_1, Event::ListenerStarted(_));
+        unwrap!(service_1.set_ext_reachability_test(false));
+
+        connect(&service_0, &event_rx_0, &service_1, &event_rx_1);
+        (service_0, event_rx_0, service_1, event_rx_1)
+    }
+
     fn connect(
         service_0: &Service,
         event_rx_0: &Receiver<Event>,
@@ -125,6 +125,9 @@ pub trait GetGlobalListenerAddrs {
     fn get_global_listener_addrs(&self) -> HashSet<PeerInfo>;
 }
 
+/// Decides whether we may connect to, or accept connect requests from, the given peer address.
+pub type ConnectFilter = Box<Fn(SocketAddr) -> bool + Send>;
+
 /// Crust specific data stored in event loop `Core`.
 /// This data can be accessed when interfacing with event loop.
 pub struct CrustData {
@@ -133,6 +136,8 @@ pub struct CrustData {
     /// Either established or in progress connections.
     pub connections: HashMap<PeerId, ConnectionId>,
     pub config: ConfigWrapper,
+    /// Filters peer addresses of direct connections. All addresses are allowed if not set.
+    pub connect_filter: Option<ConnectFilter>,
 }
 
 impl CrustData {
@@ -142,6 +147,7 @@ impl CrustData {
             our_listeners: Default::default(),
             connections: Default::default(),
             config: Default::default(),
+            connect_filter: None,
         }
     }
 }
@@ -7,7 +7,7 @@
 // specific language governing permissions and limitations relating to use of the SAFE Network
 // Software.
 
-use crate::common::{Core, CoreTimer, Message, PeerInfo, State};
+use crate::common::{Core, CoreTimer, Message, PeerInfo, State, HANDSHAKE_PRIORITY};
 use crate::nat::{util, NatError};
 use mio::net::TcpStream;
 use mio::{Poll, PollOpt, Ready, Token};
@@ -58,7 +58,7 @@ impl<T: 'static> GetExtAddr<T> {
         let state = Self {
             token,
             socket,
-            request: Some((Message::EchoAddrReq(our_pk), 0)),
+            request: Some((Message::EchoAddrReq(our_pk), HANDSHAKE_PRIORITY)),
             timeout,
             finish,
         };
@@ -10,7 +10,7 @@
 pub use self::error::NatError;
 pub use self::mapped_tcp_socket::{GetExtAddr, GetExtAddrFinish, MappedTcpSocket};
 pub use self::mapping_context::MappingContext;
-pub use self::util::ip_addr_is_global;
+pub use self::util::{ip_addr_is_global, new_reusably_bound_tcp_socket};
 
 mod error;
 mod mapped_tcp_socket;
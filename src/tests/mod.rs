@@ -15,7 +15,7 @@ pub use self::utils::{
 };
 
 use crate::common::{CrustUser, PeerInfo};
-use crate::main::{Config, Event, Service};
+use crate::main::{Config, Event, PrivConnectionInfo, PubConnectionInfo, Service};
 use crate::PeerId;
 use hamcrest2::prelude::*;
 use mio;
@@ -594,13 +594,15 @@ fn drop_peer_when_no_message_received_within_inactivity_period() {
     });
 }
 
-#[test]
-fn do_not_drop_peer_even_when_no_data_messages_are_exchanged_within_inactivity_period() {
+/// Bootstraps two services off each other and checks that neither of them loses the other one
+/// while no data messages are exchanged for longer than the inactivity period.
+fn peers_stay_connected_without_data_messages(heartbeat_jitter_ms: Option<u64>) {
     use crate::main::INACTIVITY_TIMEOUT_MS;
     use std::thread;
     use std::time::Duration;
 
-    let config0 = gen_config();
+    let mut config0 = gen_config();
+    config0.heartbeat_jitter_ms = heartbeat_jitter_ms;
     let (event_tx0, event_rx0) = get_event_sender();
     let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
     let mut service0 = unwrap!(Service::with_config(event_tx0, config0, peer_id, peer_sk));
@@ -611,6 +613,7 @@ fn do_not_drop_peer_even_when_no_data_messages_are_exchanged_within_inactivity_p
 
     let mut config1 = gen_config();
     config1.hard_coded_contacts = vec![localhost_contact_info(port0, service0.pub_key())];
+    config1.heartbeat_jitter_ms = heartbeat_jitter_ms;
 
     let (event_tx1, event_rx1) = get_event_sender();
     let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
@@ -630,3 +633,167 @@ fn do_not_drop_peer_even_when_no_data_messages_are_exchanged_within_inactivity_p
         panic!("peer lost unexpectedly");
     }
 }
+
+#[test]
+fn do_not_drop_peer_even_when_no_data_messages_are_exchanged_within_inactivity_period() {
+    peers_stay_connected_without_data_messages(None);
+}
+
+#[test]
+fn do_not_drop_peer_when_heartbeat_jitter_is_enabled() {
+    peers_stay_connected_without_data_messages(Some(u64::max_value()));
+}
+
+/// Starts a listening service with the given config.
+fn start_listening_service(config: Config) -> (Service, mpsc::Receiver<Event>) {
+    let (event_tx, event_rx) = get_event_sender();
+    let (peer_id, peer_sk) = rand_peer_id_and_enc_sk();
+    let mut service = unwrap!(Service::with_config(event_tx, config, peer_id, peer_sk));
+
+    unwrap!(service.start_listening_tcp());
+    expect_event!(event_rx, Event::ListenerStarted(_));
+    (service, event_rx)
+}
+
+fn prepare_connection_info(
+    service: &Service,
+    event_rx: &mpsc::Receiver<Event>,
+    token: u32,
+) -> PrivConnectionInfo {
+    service.prepare_connection_info(token);
+    expect_event!(event_rx, Event::ConnectionInfoPrepared(result) => {
+        assert_eq!(result.result_token, token);
+        unwrap!(result.result)
+    })
+}
+
+/// Connects to a peer which accepts TCP connections, but never answers the handshake, and
+/// checks that the connect fails within a few seconds.
+fn connect_to_unresponsive_peer_fails_quickly(config: Config) {
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    timebomb(Duration::from_secs(30), || {
+        let (service, event_rx) = start_listening_service(config);
+        let our_ci = prepare_connection_info(&service, &event_rx, 0);
+
+        let unresponsive_peer = unwrap!(TcpListener::bind("127.0.0.1:0"));
+        let (their_id, _) = rand_peer_id_and_enc_sk();
+        let their_ci = PubConnectionInfo {
+            id: their_id,
+            for_direct: vec![unwrap!(unresponsive_peer.local_addr())],
+        };
+
+        let connect_started = Instant::now();
+        unwrap!(service.connect(our_ci, their_ci));
+
+        expect_event!(event_rx, Event::ConnectFailure(id) => assert_eq!(id, their_id));
+        assert!(connect_started.elapsed() < Duration::from_secs(10));
+    })
+}
+
+#[test]
+fn connect_fails_after_configured_timeout() {
+    let mut config = gen_config();
+    config.connect_timeout_sec = Some(1);
+    config.connect_attempt_timeout_sec = Some(60);
+    connect_to_unresponsive_peer_fails_quickly(config);
+}
+
+#[test]
+fn stalled_connection_attempt_times_out() {
+    let mut config = gen_config();
+    config.connect_timeout_sec = Some(60);
+    config.connect_attempt_timeout_sec = Some(1);
+    connect_to_unresponsive_peer_fails_quickly(config);
+}
+
+#[test]
+fn refused_connect_is_retried_until_the_peer_accepts() {
+    use std::net::TcpListener;
+
+    timebomb(Duration::from_secs(30), || {
+        let mut config = gen_config();
+        config.connect_retries = Some(3);
+        config.connect_retry_delay_ms = Some(1000);
+        let (service_0, event_rx_0) = start_listening_service(config);
+        let our_ci = prepare_connection_info(&service_0, &event_rx_0, 0);
+
+        // Nothing listens on the peer's port yet, so the first attempt is refused.
+        let their_addr = unwrap!(unwrap!(TcpListener::bind("127.0.0.1:0")).local_addr());
+        let (their_id, their_sk) = rand_peer_id_and_enc_sk();
+        let their_ci = PubConnectionInfo {
+            id: their_id,
+            for_direct: vec![their_addr],
+        };
+        unwrap!(service_0.connect(our_ci, their_ci));
+        thread::sleep(Duration::from_millis(100));
+
+        let mut config = gen_config();
+        config.tcp_acceptor_port = Some(their_addr.port());
+        let (event_tx_1, event_rx_1) = get_event_sender();
+        let mut service_1 = unwrap!(Service::with_config(event_tx_1, config, their_id, their_sk));
+        unwrap!(service_1.start_listening_tcp());
+        expect_event!(event_rx_1, Event::ListenerStarted(_));
+        unwrap!(service_1.set_ext_reachability_test(false));
+
+        expect_event!(event_rx_0, Event::ConnectSuccess(id) => assert_eq!(id, their_id));
+        expect_event!(event_rx_1, Event::ConnectSuccess(id) => assert_eq!(id, service_0.id()));
+    })
+}
+
+#[test]
+fn connect_accepts_response_with_hash_of_accepted_network_name() {
+    timebomb(Duration::from_secs(30), || {
+        let mut config_0 = gen_config();
+        config_0.network_name = Some("new-network".to_owned());
+        config_0.accepted_network_names = Some(vec!["old-network".to_owned()]);
+        let (service_0, event_rx_0) = start_listening_service(config_0);
+
+        let mut config_1 = gen_config();
+        config_1.network_name = Some("old-network".to_owned());
+        config_1.accepted_network_names = Some(vec!["new-network".to_owned()]);
+        let (service_1, event_rx_1) = start_listening_service(config_1);
+        unwrap!(service_1.set_ext_reachability_test(false));
+
+        // Only service_0 connects, so its handshake has to accept the old network's hash in
+        // service_1's response.
+        let our_ci = prepare_connection_info(&service_0, &event_rx_0, 0);
+        let their_ci = prepare_connection_info(&service_1, &event_rx_1, 0);
+        unwrap!(service_0.connect(our_ci, their_ci.to_pub_connection_info()));
+
+        expect_event!(event_rx_0, Event::ConnectSuccess(id) => assert_eq!(id, service_1.id()));
+        expect_event!(event_rx_1, Event::ConnectSuccess(id) => assert_eq!(id, service_0.id()));
+    })
+}
+
+#[test]
+fn connect_is_not_restarted_while_a_retry_is_pending() {
+    use std::net::TcpListener;
+
+    timebomb(Duration::from_secs(30), || {
+        let mut config = gen_config();
+        config.connect_retries = Some(1);
+        config.connect_retry_delay_ms = Some(1000);
+        let (service, event_rx) = start_listening_service(config);
+        let our_ci_0 = prepare_connection_info(&service, &event_rx, 0);
+        let our_ci_1 = prepare_connection_info(&service, &event_rx, 1);
+
+        // Nothing listens on this address, so every attempt is refused.
+        let closed_addr = unwrap!(unwrap!(TcpListener::bind("127.0.0.1:0")).local_addr());
+        let (their_id, _) = rand_peer_id_and_enc_sk();
+        let their_ci = PubConnectionInfo {
+            id: their_id,
+            for_direct: vec![closed_addr],
+        };
+
+        unwrap!(service.connect(our_ci_0, their_ci.clone()));
+        thread::sleep(Duration::from_millis(300));
+        unwrap!(service.connect(our_ci_1, their_ci));
+
+        expect_event!(event_rx, Event::ConnectFailure(id) => assert_eq!(id, their_id));
+        if let Ok(event) = event_rx.recv_timeout(Duration::from_secs(2)) {
+            panic!("Unexpected event: {:?}", event);
+        }
+    })
+}